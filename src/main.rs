@@ -1,4 +1,5 @@
 extern crate maman;
+extern crate tokio;
 
 use std::env;
 use std::process;
@@ -6,7 +7,8 @@ use std::process;
 use maman::Spider;
 
 #[cfg(not(test))]
-fn main() {
+#[tokio::main]
+async fn main() {
     let url = match env::args().nth(1) {
         Some(url) => url,
         None => {
@@ -16,5 +18,5 @@ fn main() {
     };
 
     let mut spider = Spider::new(url);
-    spider.crawl()
+    spider.crawl().await
 }
@@ -1,30 +1,52 @@
 use std::env;
-use std::io::Read;
-use std::error::Error;
-use std::ascii::AsciiExt;
 use std::default::Default;
-use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, HashSet};
 
-use rand::{Rng, thread_rng};
-use time::now_utc;
 use tendril::SliceExt;
 use url::{Url, ParseError};
-use hyper::header::Connection;
-use hyper::Client as HyperClient;
-use hyper::client::Response as HttpResponse;
-use redis::Client as RedisClient;
-use redis::{Commands, RedisResult};
+use reqwest::{Client as ReqwestClient, StatusCode};
+use reqwest::header::LOCATION;
+use redis::RedisError;
 use rustc_serialize::json::{ToJson, Json};
 use html5ever::tokenizer::{TokenSink, Token, TagToken, Tokenizer};
+use robotparser::http::RobotFileParser;
+use encoding::{Encoding, DecoderTrap};
+use encoding::all::UTF_8;
+use encoding::label::encoding_from_whatwg_label;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task;
+
+use self::sidekiq::{Client as SidekiqClient, ClientOpts, create_redis_pool};
+
+mod sidekiq;
 
 const MAMAN_ENV: &'static str = "MAMAN_ENV";
+const MAMAN_LIMIT: &'static str = "MAMAN_LIMIT";
+const REDIS_URL_ENV: &'static str = "REDIS_URL";
+const DEFAULT_USER_AGENT: &'static str = "maman";
+const DEFAULT_LIMIT: isize = -1;
+const DEFAULT_REDIRECT_LIMIT: u8 = 10;
+const DEFAULT_CONCURRENCY: usize = 1;
+const DEFAULT_REDIS_URL: &'static str = "redis://127.0.0.1/";
+const SIDEKIQ_QUEUE: &'static str = "maman";
+const SIDEKIQ_CLASS: &'static str = "Maman";
 
 pub struct Spider {
     pub base_url: String,
-    pub visited_urls: Vec<Url>,
-    pub unvisited_urls: Vec<Url>,
     pub env: String,
-    pub redis_queue_name: String,
+    pub user_agent: String,
+    pub require_robots: bool,
+    pub limit: isize,
+    pub redirect_limit: u8,
+    pub concurrency: usize,
+    client: ReqwestClient,
+    robots_cache: Arc<Mutex<BTreeMap<String, Option<Arc<RobotFileParser>>>>>,
+    visited_urls: Arc<Mutex<HashSet<Url>>>,
+    redis_url: String,
+    sidekiq_opts: ClientOpts,
+    sidekiq_client: Arc<Mutex<Option<SidekiqClient>>>,
 }
 
 pub struct Page {
@@ -32,27 +54,26 @@ pub struct Page {
     pub document: String,
     pub headers: BTreeMap<String, String>,
     pub urls: Vec<Url>,
-    pub jid: String,
+    pub canonical_url: Option<Url>,
+    pub feeds: Vec<Url>,
+    pub webmention: Option<Url>,
+    user_agent: String,
+    robots: Option<Arc<RobotFileParser>>,
 }
 
 impl ToJson for Page {
     fn to_json(&self) -> Json {
-        let mut root = BTreeMap::new();
         let mut object = BTreeMap::new();
-        let mut args = Vec::new();
         object.insert("url".to_string(), self.url.to_string().to_json());
         object.insert("document".to_string(), self.document.to_json());
         object.insert("headers".to_string(), self.headers.to_json());
-        args.push(object);
-        root.insert("class".to_string(), "Maman".to_json());
-        root.insert("retry".to_string(), true.to_json());
-        root.insert("args".to_string(), args.to_json());
-        root.insert("jid".to_string(), self.jid.to_json());
-        root.insert("created_at".to_string(),
-                    now_utc().to_timespec().sec.to_json());
-        root.insert("enqueued_at".to_string(),
-                    now_utc().to_timespec().sec.to_json());
-        Json::Object(root)
+        object.insert("canonical_url".to_string(),
+                      self.canonical_url.as_ref().map(|u| u.to_string()).to_json());
+        object.insert("feeds".to_string(),
+                      self.feeds.iter().map(|u| u.to_string()).collect::<Vec<_>>().to_json());
+        object.insert("webmention".to_string(),
+                      self.webmention.as_ref().map(|u| u.to_string()).to_json());
+        Json::Object(object)
     }
 }
 
@@ -61,7 +82,7 @@ impl TokenSink for Page {
         match token {
             TagToken(tag) => {
                 match tag.name {
-                    atom!("a") => {
+                    atom!("a") | atom!("area") => {
                         for attr in tag.attrs.iter() {
                             if attr.name.local.to_string() == "href" {
                                 match self.can_enqueue(&attr.value) {
@@ -73,6 +94,57 @@ impl TokenSink for Page {
                             }
                         }
                     }
+                    atom!("iframe") => {
+                        for attr in tag.attrs.iter() {
+                            if attr.name.local.to_string() == "src" {
+                                match self.can_enqueue(&attr.value) {
+                                    Some(u) => {
+                                        self.urls.push(u);
+                                    }
+                                    None => {}
+                                }
+                            }
+                        }
+                    }
+                    atom!("link") => {
+                        let mut rel = None;
+                        let mut href = None;
+                        let mut link_type = None;
+                        for attr in tag.attrs.iter() {
+                            if attr.name.local.to_string() == "rel" {
+                                rel = Some(attr.value.to_string());
+                            } else if attr.name.local.to_string() == "href" {
+                                href = Some(attr.value.to_string());
+                            } else if attr.name.local.to_string() == "type" {
+                                link_type = Some(attr.value.to_string());
+                            }
+                        }
+                        let is_feed_type = match link_type {
+                            Some(ref t) => {
+                                let t = t.to_ascii_lowercase();
+                                t.contains("rss") || t.contains("atom")
+                            }
+                            None => false,
+                        };
+                        if let (Some(rel), Some(href)) = (rel, href) {
+                            for keyword in rel.to_ascii_lowercase().split_whitespace() {
+                                match keyword {
+                                    "canonical" => {
+                                        self.canonical_url = self.parsed_url_without_fragment(&href);
+                                    }
+                                    "alternate" if is_feed_type => {
+                                        if let Some(u) = self.parsed_url_without_fragment(&href) {
+                                            self.feeds.push(u);
+                                        }
+                                    }
+                                    "webmention" => {
+                                        self.webmention = self.parsed_url_without_fragment(&href);
+                                    }
+                                    _ => {}
+                                }
+                            }
+                        }
+                    }
                     _ => {}
                 }
             }
@@ -82,21 +154,29 @@ impl TokenSink for Page {
 }
 
 impl Page {
-    pub fn new(url: Url, document: String, headers: BTreeMap<String, String>) -> Page {
-        let jid = thread_rng().gen_ascii_chars().take(24).collect::<String>();
+    pub fn new(url: Url,
+               document: String,
+               headers: BTreeMap<String, String>,
+               user_agent: String,
+               robots: Option<Arc<RobotFileParser>>)
+               -> Page {
         Page {
             url: url,
             document: document,
             headers: headers,
             urls: Vec::new(),
-            jid: jid,
+            canonical_url: None,
+            feeds: Vec::new(),
+            webmention: None,
+            user_agent: user_agent,
+            robots: robots,
         }
     }
 
     fn parsed_url(&self, url: &str) -> Option<Url> {
         match Url::parse(url) {
             Ok(u) => Some(u),
-            Err(ParseError::RelativeUrlWithoutBase) => Some(self.url.join(url).unwrap()),
+            Err(ParseError::RelativeUrlWithoutBase) => self.url.join(url).ok(),
             Err(_) => None,
         }
     }
@@ -119,12 +199,19 @@ impl Page {
         self.url.domain() == url.domain()
     }
 
+    fn can_fetch(&self, url: &Url) -> bool {
+        match self.robots {
+            Some(ref parser) => parser.can_fetch(&self.user_agent, url.path()),
+            None => true,
+        }
+    }
+
     fn can_enqueue(&self, url: &str) -> Option<Url> {
         match self.parsed_url_without_fragment(url) {
             Some(u) => {
                 match u.scheme() {
                     "http" | "https" => {
-                        if !self.url_eq(&u) && self.domain_eq(&u) {
+                        if !self.url_eq(&u) && self.domain_eq(&u) && self.can_fetch(&u) {
                             Some(u)
                         } else {
                             None
@@ -141,46 +228,128 @@ impl Page {
 impl Spider {
     pub fn new(base_url: String) -> Spider {
         let maman_env = env::var(&MAMAN_ENV.to_string()).unwrap_or("development".to_string());
-        let redis_queue_name = format!("{}:{}:{}", maman_env, "queue", "maman");
+        let limit = env::var(&MAMAN_LIMIT.to_string())
+            .ok()
+            .and_then(|l| l.parse::<isize>().ok())
+            .unwrap_or(DEFAULT_LIMIT);
+        let redis_url = env::var(&REDIS_URL_ENV.to_string()).unwrap_or(DEFAULT_REDIS_URL.to_string());
+        let sidekiq_opts = ClientOpts {
+            namespace: Some(maman_env.clone()),
+            queue: SIDEKIQ_QUEUE.to_string(),
+        };
         Spider {
             base_url: base_url,
-            visited_urls: Vec::new(),
-            unvisited_urls: Vec::new(),
             env: maman_env,
-            redis_queue_name: redis_queue_name,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            require_robots: false,
+            limit: limit,
+            redirect_limit: DEFAULT_REDIRECT_LIMIT,
+            concurrency: DEFAULT_CONCURRENCY,
+            client: ReqwestClient::new(),
+            robots_cache: Arc::new(Mutex::new(BTreeMap::new())),
+            visited_urls: Arc::new(Mutex::new(HashSet::new())),
+            redis_url: redis_url,
+            sidekiq_opts: sidekiq_opts,
+            sidekiq_client: Arc::new(Mutex::new(None)),
         }
     }
 
+    pub fn set_limit(&mut self, limit: isize) {
+        self.limit = limit;
+    }
+
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = concurrency;
+    }
+
     pub fn is_visited(&self, url: &Url) -> bool {
-        self.visited_urls.contains(url)
+        self.visited_urls.lock().unwrap().contains(url)
     }
 
-    pub fn visited_urls(&self) -> &Vec<Url> {
-        &self.visited_urls
+    pub fn visited_urls(&self) -> Vec<Url> {
+        self.visited_urls.lock().unwrap().iter().cloned().collect()
     }
 
-    pub fn read_response(&self, page_url: &str, mut response: HttpResponse) -> Option<Page> {
-        match Url::parse(page_url) {
-            Ok(u) => {
-                let mut headers = BTreeMap::new();
-                {
-                    for h in response.headers.iter() {
-                        headers.insert(h.name().to_ascii_lowercase(), h.value_string());
-                    }
-                }
-                let mut document = String::new();
-                // handle CharsError::NotUtf8
-                match response.read_to_string(&mut document) {
-                    Ok(_) => {
-                        let page = Page::new(u, document.to_string(), headers.clone());
-                        let read = self.read_page(page, &document).unwrap();
-                        Some(read)
+    fn authority(url: &Url) -> String {
+        match url.port() {
+            Some(port) => format!("{}://{}:{}", url.scheme(), url.host_str().unwrap_or(""), port),
+            None => format!("{}://{}", url.scheme(), url.host_str().unwrap_or("")),
+        }
+    }
+
+    // `RobotFileParser::read()` never reports whether its fetch actually succeeded, so
+    // probe the URL ourselves first to tell "no robots.txt restrictions" apart from
+    // "host unreachable" before deciding whether `require_robots` should skip it. The
+    // cache is also keyed lazily per authority here instead of being populated once for
+    // `base_url` at construction, so hosts discovered later via redirects or links on a
+    // different scheme/port get their own robots.txt fetch too.
+    async fn ensure_robots(client: &ReqwestClient,
+                            cache: &Mutex<BTreeMap<String, Option<Arc<RobotFileParser>>>>,
+                            authority: &str)
+                            -> Option<Arc<RobotFileParser>> {
+        {
+            let cache = cache.lock().unwrap();
+            if let Some(entry) = cache.get(authority) {
+                return entry.clone();
+            }
+        }
+        let robots_url = format!("{}/robots.txt", authority);
+        let entry = match client.get(&robots_url).send().await {
+            Ok(_) => {
+                let parser = task::spawn_blocking(move || {
+                        let parser = RobotFileParser::new(&robots_url);
+                        parser.read();
+                        parser
+                    })
+                    .await
+                    .unwrap();
+                Some(Arc::new(parser))
+            }
+            Err(_) => None,
+        };
+        cache.lock().unwrap().insert(authority.to_string(), entry.clone());
+        entry
+    }
+
+    fn charset_from_headers(headers: &BTreeMap<String, String>) -> Option<String> {
+        match headers.get("content-type") {
+            Some(content_type) => {
+                for part in content_type.split(';').skip(1) {
+                    let part = part.trim();
+                    if part.to_ascii_lowercase().starts_with("charset=") {
+                        return Some(part[8..].trim_matches('"').to_string());
                     }
-                    Err(_) => None,
                 }
+                None
             }
-            Err(_) => None,
+            None => None,
+        }
+    }
+
+    fn decode_body(headers: &BTreeMap<String, String>, body: &[u8]) -> String {
+        let charset = Spider::charset_from_headers(headers).unwrap_or("utf-8".to_string());
+        let encoding = encoding_from_whatwg_label(&charset).unwrap_or(UTF_8 as &Encoding);
+        encoding.decode(body, DecoderTrap::Replace).unwrap_or(String::new())
+    }
+
+    // Building the pool eagerly in `Spider::new` would panic the whole process if Redis
+    // isn't up yet (r2d2's builder establishes connections at build time), so the pool is
+    // built lazily on first push instead and cached for reuse. The lock is held across the
+    // build itself (not just the cache check) so that under `concurrency > 1` concurrent
+    // callers block on the one build in flight instead of each racing to stand up their
+    // own pool — a short stall beats a connection storm.
+    fn sidekiq_client(redis_url: &str,
+                       opts: ClientOpts,
+                       cached: &Mutex<Option<SidekiqClient>>)
+                       -> Result<SidekiqClient, RedisError> {
+        let mut guard = cached.lock().unwrap();
+        if let Some(ref client) = *guard {
+            return Ok(client.clone());
         }
+        let pool = try!(create_redis_pool(redis_url));
+        let client = SidekiqClient::new(pool, opts);
+        *guard = Some(client.clone());
+        Ok(client)
     }
 
     pub fn read_page(&self, page: Page, document: &str) -> Tokenizer<Page> {
@@ -190,62 +359,340 @@ impl Spider {
         tok
     }
 
-    pub fn visit_page(&mut self, page: Page) {
-        self.add_visited_url(page.url.clone());
-        for u in page.urls.iter() {
-            self.add_unvisited_url(u.clone());
+    fn is_redirect(status: StatusCode) -> bool {
+        match status {
+            StatusCode::MOVED_PERMANENTLY |
+            StatusCode::FOUND |
+            StatusCode::SEE_OTHER |
+            StatusCode::TEMPORARY_REDIRECT |
+            StatusCode::PERMANENT_REDIRECT => true,
+            _ => false,
         }
-        match self.send_to_redis(page) {
-            Err(err) => {
-                println!("Redis {}: {}", err.category(), err.description());
+    }
+
+    fn resolve_redirect(current: &str, location: &str) -> Option<String> {
+        match Url::parse(current) {
+            Ok(base) => {
+                match base.join(location) {
+                    Ok(u) => Some(u.to_string()),
+                    Err(_) => None,
+                }
             }
-            Ok(()) => {}
+            Err(_) => None,
         }
     }
 
-    pub fn visit(&mut self, page_url: &str, response: HttpResponse) {
-        if let Some(page) = self.read_response(page_url, response) {
-            self.visit_page(page);
+    async fn fetch(client: &ReqwestClient,
+                    redirect_limit: u8,
+                    url: &str)
+                    -> Option<(String, BTreeMap<String, String>, Vec<u8>)> {
+        let mut current = url.to_string();
+        let mut hops_left = redirect_limit;
+        loop {
+            let response = match client.get(&current).send().await {
+                Ok(response) => response,
+                Err(_) => return None,
+            };
+            if Spider::is_redirect(response.status()) {
+                if hops_left == 0 {
+                    return None;
+                }
+                let location = match response.headers().get(LOCATION) {
+                    Some(location) => {
+                        match location.to_str() {
+                            Ok(location) => location.to_string(),
+                            Err(_) => return None,
+                        }
+                    }
+                    None => return None,
+                };
+                match Spider::resolve_redirect(&current, &location) {
+                    Some(next) => {
+                        current = next;
+                        hops_left -= 1;
+                    }
+                    None => return None,
+                }
+            } else {
+                let mut headers = BTreeMap::new();
+                for (name, value) in response.headers().iter() {
+                    if let Ok(value) = value.to_str() {
+                        headers.insert(name.as_str().to_ascii_lowercase(), value.to_string());
+                    }
+                }
+                let body = match response.bytes().await {
+                    Ok(body) => body.to_vec(),
+                    Err(_) => return None,
+                };
+                return Some((current, headers, body));
+            }
         }
     }
 
-    pub fn crawl(&mut self) {
-        let base_url = self.base_url.clone();
-        if let Some(response) = self.load_url(&base_url) {
-            self.visit(&base_url, response);
-            while let Some(url) = self.unvisited_urls.pop() {
-                if !self.is_visited(&url) {
-                    let url_ser = &url.to_string();
-                    if let Some(response) = self.load_url(url_ser) {
-                        self.visit(url_ser, response);
+    pub async fn crawl(&mut self) {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Url>();
+        let semaphore = Arc::new(Semaphore::new(self.concurrency.max(1)));
+        let active = Arc::new(AtomicUsize::new(0));
+        let idle = Arc::new(tokio::sync::Notify::new());
+
+        if let Ok(u) = Url::parse(&self.base_url) {
+            active.fetch_add(1, Ordering::SeqCst);
+            let _ = tx.send(u);
+        }
+
+        let mut workers = Vec::new();
+        while active.load(Ordering::SeqCst) > 0 {
+            let url = tokio::select! {
+                received = rx.recv() => match received {
+                    Some(url) => url,
+                    None => break,
+                },
+                _ = idle.notified() => continue,
+            };
+
+            let permit = semaphore.clone().acquire_owned().await.unwrap();
+            if self.limit >= 0 && self.visited_urls.lock().unwrap().len() as isize >= self.limit {
+                active.fetch_sub(1, Ordering::SeqCst);
+                continue;
+            }
+            if self.is_visited(&url) {
+                active.fetch_sub(1, Ordering::SeqCst);
+                continue;
+            }
+
+            let client = self.client.clone();
+            let redirect_limit = self.redirect_limit;
+            let user_agent = self.user_agent.clone();
+            let robots_cache = self.robots_cache.clone();
+            let require_robots = self.require_robots;
+            let redis_url = self.redis_url.clone();
+            let sidekiq_opts = self.sidekiq_opts.clone();
+            let sidekiq_client = self.sidekiq_client.clone();
+            let visited_urls = self.visited_urls.clone();
+            let tx = tx.clone();
+            let active = active.clone();
+            let idle = idle.clone();
+
+            workers.push(task::spawn(async move {
+                let _permit = permit;
+                let authority = Spider::authority(&url);
+                let robots = Spider::ensure_robots(&client, &robots_cache, &authority).await;
+                if require_robots && robots.is_none() {
+                    if active.fetch_sub(1, Ordering::SeqCst) == 1 {
+                        idle.notify_one();
                     }
+                    return;
                 }
-            }
+
+                if let Some((final_url, headers, body)) =
+                    Spider::fetch(&client, redirect_limit, url.as_str()).await {
+                    if let Ok(page_url) = Url::parse(&final_url) {
+                        let already_visited = {
+                            let mut seen = visited_urls.lock().unwrap();
+                            !seen.insert(page_url.clone())
+                        };
+                        if !already_visited {
+                            let document = Spider::decode_body(&headers, &body);
+                            let final_authority = Spider::authority(&page_url);
+                            let page_robots = if final_authority == authority {
+                                robots
+                            } else {
+                                Spider::ensure_robots(&client, &robots_cache, &final_authority).await
+                            };
+                            let page = Page::new(page_url, document.clone(), headers, user_agent, page_robots);
+                            let mut tok = Tokenizer::new(page, Default::default());
+                            tok.feed(document.to_tendril());
+                            tok.end();
+                            let page = tok.sink;
+                            for discovered in page.urls.iter() {
+                                active.fetch_add(1, Ordering::SeqCst);
+                                let _ = tx.send(discovered.clone());
+                            }
+                            let push_result = task::spawn_blocking(move || {
+                                    Spider::sidekiq_client(&redis_url, sidekiq_opts, &sidekiq_client)
+                                        .and_then(|client| client.push(SIDEKIQ_CLASS, page.to_json()))
+                                })
+                                .await
+                                .unwrap();
+                            if let Err(err) = push_result {
+                                println!("Redis {}: {}", err.category(), err.to_string());
+                            }
+                        }
+                    }
+                }
+                if active.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    idle.notify_one();
+                }
+            }));
+        }
+
+        for worker in workers {
+            let _ = worker.await;
         }
     }
+}
 
-    fn send_to_redis(&self, page: Page) -> RedisResult<()> {
-        let client = try!(RedisClient::open("redis://127.0.0.1/"));
-        let connection = try!(client.get_connection());
-        let _: () = try!(connection.lpush(self.redis_queue_name.to_string(), page.to_json()));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
 
-        Ok(())
+    #[test]
+    fn parsed_url_returns_none_instead_of_panicking_on_unjoinable_relative_href() {
+        // "mailto:" URLs cannot be a base, so joining any relative reference against one
+        // fails — this used to reach an `.unwrap()` and panic a crawl worker.
+        let base = Url::parse("mailto:ghost@example.com").unwrap();
+        let page = Page::new(base, String::new(), BTreeMap::new(), "maman".to_string(), None);
+        assert_eq!(page.parsed_url("/not-a-real-path"), None);
     }
 
-    fn load_url(&self, url: &str) -> Option<HttpResponse> {
-        let client = HyperClient::new();
-        let res = client.get(url).header(Connection::close()).send();
-        match res {
-            Ok(response) => Some(response),
-            Err(_) => None,
-        }
+    #[test]
+    fn charset_from_headers_extracts_charset_param() {
+        let mut headers = BTreeMap::new();
+        headers.insert("content-type".to_string(), "text/html; charset=iso-8859-1".to_string());
+        assert_eq!(Spider::charset_from_headers(&headers), Some("iso-8859-1".to_string()));
+    }
+
+    #[test]
+    fn decode_body_decodes_non_utf8_body_using_charset_header() {
+        let mut headers = BTreeMap::new();
+        headers.insert("content-type".to_string(), "text/html; charset=iso-8859-1".to_string());
+        // "café" with 'é' encoded as the single Latin-1/windows-1252 byte 0xE9, which is
+        // not valid UTF-8 on its own — decoding as UTF-8 would have silently emptied this.
+        let body = vec![0x63, 0x61, 0x66, 0xE9];
+        assert_eq!(Spider::decode_body(&headers, &body), "café");
+    }
+
+    #[test]
+    fn is_redirect_true_for_redirect_statuses() {
+        assert!(Spider::is_redirect(StatusCode::MOVED_PERMANENTLY));
+        assert!(Spider::is_redirect(StatusCode::FOUND));
+        assert!(Spider::is_redirect(StatusCode::SEE_OTHER));
+        assert!(Spider::is_redirect(StatusCode::TEMPORARY_REDIRECT));
+        assert!(Spider::is_redirect(StatusCode::PERMANENT_REDIRECT));
+    }
+
+    #[test]
+    fn is_redirect_false_for_non_redirect_statuses() {
+        assert!(!Spider::is_redirect(StatusCode::OK));
+        assert!(!Spider::is_redirect(StatusCode::NOT_FOUND));
+        assert!(!Spider::is_redirect(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+
+    #[test]
+    fn resolve_redirect_resolves_relative_location() {
+        let resolved = Spider::resolve_redirect("http://example.com/a/b", "c").unwrap();
+        assert_eq!(resolved, "http://example.com/a/c");
+    }
+
+    #[test]
+    fn resolve_redirect_honors_absolute_location() {
+        let resolved = Spider::resolve_redirect("http://example.com/a", "http://other.com/b").unwrap();
+        assert_eq!(resolved, "http://other.com/b");
+    }
+
+    #[test]
+    fn resolve_redirect_returns_none_for_unparseable_current() {
+        assert_eq!(Spider::resolve_redirect("not a url", "/b"), None);
+    }
+
+    // Minimal raw-TCP HTTP server so `crawl()` can be exercised end to end without a real
+    // network or Redis: every request gets back whatever body `routes` has for its path,
+    // or a 404 for anything else (such as `/robots.txt`, which `ensure_robots` always
+    // probes, when no route is given for it).
+    async fn serve_fake_site(routes: HashMap<&'static str, &'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        task::spawn(async move {
+            loop {
+                let (mut socket, _) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(_) => return,
+                };
+                let routes = routes.clone();
+                task::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    let n = match socket.read(&mut buf).await {
+                        Ok(n) => n,
+                        Err(_) => return,
+                    };
+                    let request = String::from_utf8_lossy(&buf[..n]);
+                    let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+                    let response = match routes.get(path.as_str()) {
+                        Some(body) => {
+                            format!("HTTP/1.1 200 OK\r\nContent-Type: text/html; \
+                                     charset=utf-8\r\nContent-Length: {}\r\nConnection: \
+                                     close\r\n\r\n{}",
+                                    body.len(),
+                                    body)
+                        }
+                        None => {
+                            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: \
+                             close\r\n\r\n"
+                                .to_string()
+                        }
+                    };
+                    let _ = socket.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn crawl_at_concurrency_one_dedups_and_enqueues_discovered_links() {
+        let mut routes = HashMap::new();
+        routes.insert("/", "<a href=\"/a\">a</a><a href=\"/b\">b</a>");
+        routes.insert("/a", "<a href=\"/\">home</a><a href=\"/b\">b</a>");
+        routes.insert("/b", "<a href=\"/\">home</a>");
+        let base_url = serve_fake_site(routes).await;
+
+        let mut spider = Spider::new(base_url.clone());
+        spider.set_concurrency(1);
+        spider.crawl().await;
+
+        let mut visited: Vec<String> = spider.visited_urls().iter().map(|u| u.path().to_string()).collect();
+        visited.sort();
+        assert_eq!(visited, vec!["/", "/a", "/b"]);
     }
 
-    fn add_visited_url(&mut self, url: Url) {
-        self.visited_urls.push(url);
+    #[tokio::test]
+    async fn crawl_stops_visiting_once_limit_is_reached() {
+        let mut routes = HashMap::new();
+        routes.insert("/", "<a href=\"/a\">a</a><a href=\"/b\">b</a>");
+        routes.insert("/a", "<a href=\"/c\">c</a>");
+        routes.insert("/b", "");
+        routes.insert("/c", "");
+        let base_url = serve_fake_site(routes).await;
+
+        let mut spider = Spider::new(base_url.clone());
+        spider.set_concurrency(1);
+        spider.set_limit(2);
+        spider.crawl().await;
+
+        assert!(spider.visited_urls().len() as isize <= spider.limit);
     }
 
-    fn add_unvisited_url(&mut self, url: Url) {
-        self.unvisited_urls.push(url);
+    #[tokio::test]
+    async fn crawl_never_visits_a_path_disallowed_by_robots_txt() {
+        let mut routes = HashMap::new();
+        routes.insert("/", "<a href=\"/forbidden\">nope</a><a href=\"/allowed\">yes</a>");
+        routes.insert("/robots.txt", "User-agent: *\nDisallow: /forbidden\n");
+        routes.insert("/forbidden", "");
+        routes.insert("/allowed", "");
+        let base_url = serve_fake_site(routes).await;
+
+        let mut spider = Spider::new(base_url.clone());
+        spider.set_concurrency(1);
+        spider.crawl().await;
+
+        let visited: Vec<String> = spider.visited_urls().iter().map(|u| u.path().to_string()).collect();
+        assert!(visited.contains(&"/".to_string()));
+        assert!(visited.contains(&"/allowed".to_string()));
+        assert!(!visited.contains(&"/forbidden".to_string()));
     }
 }
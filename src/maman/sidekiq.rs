@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+
+use r2d2::{Pool, Error as PoolError};
+use r2d2_redis::RedisConnectionManager;
+use redis::{Commands, RedisResult, RedisError, ErrorKind};
+use rustc_serialize::json::{Json, ToJson};
+use rand::{Rng, thread_rng};
+use time::now_utc;
+
+pub type RedisPool = Pool<RedisConnectionManager>;
+
+#[derive(Clone)]
+pub struct ClientOpts {
+    pub namespace: Option<String>,
+    pub queue: String,
+}
+
+impl Default for ClientOpts {
+    fn default() -> ClientOpts {
+        ClientOpts {
+            namespace: None,
+            queue: "default".to_string(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Client {
+    pool: RedisPool,
+    opts: ClientOpts,
+}
+
+impl Client {
+    pub fn new(pool: RedisPool, opts: ClientOpts) -> Client {
+        Client {
+            pool: pool,
+            opts: opts,
+        }
+    }
+
+    fn queue_key(&self) -> String {
+        match self.opts.namespace {
+            Some(ref namespace) => format!("{}:queue:{}", namespace, self.opts.queue),
+            None => format!("queue:{}", self.opts.queue),
+        }
+    }
+
+    pub fn push(&self, class: &str, args: Json) -> RedisResult<()> {
+        let jid = thread_rng().gen_ascii_chars().take(24).collect::<String>();
+        let mut job = BTreeMap::new();
+        job.insert("class".to_string(), class.to_json());
+        job.insert("args".to_string(), Json::Array(vec![args]));
+        job.insert("retry".to_string(), true.to_json());
+        job.insert("jid".to_string(), jid.to_json());
+        job.insert("created_at".to_string(), now_utc().to_timespec().sec.to_json());
+        job.insert("enqueued_at".to_string(), now_utc().to_timespec().sec.to_json());
+
+        let connection = try!(self.pool.get().map_err(Client::pool_error));
+        let _: () = try!(connection.lpush(self.queue_key(), Json::Object(job).to_string()));
+        Ok(())
+    }
+
+    fn pool_error(err: PoolError) -> RedisError {
+        RedisError::from((ErrorKind::IoError, "redis pool exhausted", err.to_string()))
+    }
+}
+
+pub fn create_redis_pool(redis_url: &str) -> RedisResult<RedisPool> {
+    let manager = try!(RedisConnectionManager::new(redis_url)
+        .map_err(|err| RedisError::from((ErrorKind::IoError, "invalid redis url", err.to_string()))));
+    Pool::builder()
+        .build(manager)
+        .map_err(|err| RedisError::from((ErrorKind::IoError, "redis pool build failed", err.to_string())))
+}